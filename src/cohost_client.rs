@@ -0,0 +1,29 @@
+//! Builds the single `reqwest::Client` every command that talks to cohost.org should use, so
+//! that the `COHOST_COOKIE`-gated private/logged-in-only content is available consistently
+//! everywhere we hit cohost’s api or cdn, not just in `cohost2json`.
+
+use std::env;
+
+use jane_eyre::eyre;
+use reqwest::{
+    header::{self, HeaderMap, HeaderValue},
+    Client,
+};
+use tracing::info;
+
+/// if `COHOST_COOKIE` is set, the returned client sends `connect.sid` on every request, so
+/// private or logged-in-only chosts (and their attachments) are reachable; otherwise it is a
+/// plain, unauthenticated client.
+pub fn build_client() -> eyre::Result<Client> {
+    if let Ok(connect_sid) = env::var("COHOST_COOKIE") {
+        info!("COHOST_COOKIE is set; requests will include private or logged-in-only content!");
+        let mut cookie_value = HeaderValue::from_str(&format!("connect.sid={connect_sid}"))?;
+        cookie_value.set_sensitive(true);
+        let mut headers = HeaderMap::new();
+        headers.insert(header::COOKIE, cookie_value);
+        Ok(Client::builder().default_headers(headers).build()?)
+    } else {
+        info!("COHOST_COOKIE not set; requests will exclude private or logged-in-only content!");
+        Ok(Client::builder().build()?)
+    }
+}
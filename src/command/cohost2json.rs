@@ -1,24 +1,76 @@
 use std::{
-    env::{self},
+    collections::BTreeMap,
     fs::{create_dir_all, File},
     path::Path,
 };
 
+use futures::stream::{self, StreamExt};
 use jane_eyre::eyre::{self, bail, OptionExt};
-use reqwest::{
-    header::{self, HeaderMap, HeaderValue},
-    Client,
-};
-use tracing::info;
+use reqwest::{header, StatusCode};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{debug, info};
 
 use crate::cohost::{
     ListEditedProjectsResponse, LoggedInResponse, Post, PostsResponse, TrpcResponse,
 };
+use crate::cohost_client::build_client;
+use crate::retry::{get_with_retry, RateLimiter};
 
 #[derive(clap::Args, Debug)]
 pub struct Cohost2json {
     pub project_name: String,
     pub path_to_chosts: String,
+
+    /// re-download every chost, ignoring the index left behind by a previous run.
+    #[arg(long)]
+    pub full: bool,
+
+    /// how many post json files to write at once.
+    #[arg(long, default_value_t = 8)]
+    pub concurrency: usize,
+
+    /// maximum number of requests to make per second, across retries.
+    #[arg(long, default_value_t = 4)]
+    pub requests_per_second: u32,
+}
+
+/// the name of the index file we leave in `path_to_chosts`, tracking enough per-post state to
+/// avoid re-fetching posts we already have an up-to-date copy of.
+const INDEX_FILENAME: &str = ".cohost2json-index.json";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Index {
+    /// postId -> state we saw it in last time, used to skip re-writing posts that have not
+    /// changed since.
+    posts: BTreeMap<String, IndexedPost>,
+    /// page number -> the `ETag` the posts endpoint sent us for it last time, so we can send
+    /// `If-None-Match` and get a cheap 304 if the page has not changed.
+    page_etags: BTreeMap<u32, String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct IndexedPost {
+    /// sha256 of the post’s json as cohost sent it to us, last time we wrote it. cohost’s
+    /// `publishedAt` is stable across edits, so it cannot tell us whether a post changed; hashing
+    /// the whole post can.
+    content_hash: String,
+}
+
+impl Index {
+    fn load(path: &Path) -> eyre::Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let file = File::open(path)?;
+        Ok(serde_json::from_reader(file)?)
+    }
+
+    fn save(&self, path: &Path) -> eyre::Result<()> {
+        let file = File::create(path)?;
+        serde_json::to_writer(file, self)?;
+        Ok(())
+    }
 }
 
 pub async fn main(args: Cohost2json) -> eyre::Result<()> {
@@ -27,14 +79,9 @@ pub async fn main(args: Cohost2json) -> eyre::Result<()> {
     let output_path = Path::new(&output_path);
     create_dir_all(output_path)?;
 
-    let client = if let Ok(connect_sid) = env::var("COHOST_COOKIE") {
-        info!("COHOST_COOKIE is set; output will include private or logged-in-only chosts!");
-        let mut cookie_value = HeaderValue::from_str(&format!("connect.sid={connect_sid}"))?;
-        cookie_value.set_sensitive(true);
-        let mut headers = HeaderMap::new();
-        headers.insert(header::COOKIE, cookie_value);
-        let client = Client::builder().default_headers(headers).build()?;
+    let client = build_client()?;
 
+    if std::env::var("COHOST_COOKIE").is_ok() {
         let edited_projects = client
             .get("https://cohost.org/api/v1/trpc/projects.listEditedProjects")
             .send()
@@ -84,18 +131,41 @@ pub async fn main(args: Cohost2json) -> eyre::Result<()> {
                 requested_project
             );
         }
+    }
 
-        client
+    let index_path = output_path.join(INDEX_FILENAME);
+    let mut index = if args.full {
+        Index::default()
     } else {
-        info!("COHOST_COOKIE not set; output will exclude private or logged-in-only chosts!");
-        Client::builder().build()?
+        Index::load(&index_path)?
     };
+    let rate_limiter = RateLimiter::new(args.requests_per_second);
 
-    for page in 0.. {
+    // page discovery has to stay sequential: which page we ask for next (and whether we stop)
+    // depends on what the previous page told us.
+    'pages: for page in 0u32.. {
         let url =
             format!("https://cohost.org/api/v1/project/{requested_project}/posts?page={page}");
         info!("GET {url}");
-        let response: PostsResponse = client.get(url).send().await?.json().await?;
+        let response = get_with_retry(
+            &client,
+            &rate_limiter,
+            &url,
+            index.page_etags.get(&page).map(String::as_str),
+        )
+        .await?;
+
+        if response.status() == StatusCode::NOT_MODIFIED {
+            debug!("page {page} not modified since last run; stopping early");
+            break;
+        }
+
+        let etag = response
+            .headers()
+            .get(header::ETAG)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+        let response: PostsResponse = response.error_for_status()?.json().await?;
 
         // nItems may be zero if none of the posts on this page are currently visible,
         // but nPages will only be zero when we have run out of pages.
@@ -103,12 +173,51 @@ pub async fn main(args: Cohost2json) -> eyre::Result<()> {
             break;
         }
 
+        // figure out, synchronously, which posts on this page are actually new or changed...
+        let mut to_write = vec![];
+        let mut any_new_or_changed = response.items.is_empty();
         for post_value in response.items {
             let post: Post = serde_json::from_value(post_value.clone())?;
-            let path = output_path.join(format!("{}.json", post.postId));
-            info!("Writing {path:?}");
-            let output_file = File::create(path)?;
-            serde_json::to_writer(output_file, &post_value)?;
+            let content_hash = format!("{:x}", Sha256::digest(post_value.to_string()));
+
+            if let Some(existing) = index.posts.get(&post.postId) {
+                if existing.content_hash == content_hash {
+                    debug!("{} unchanged since last run; skipping", post.postId);
+                    continue;
+                }
+            }
+            any_new_or_changed = true;
+            to_write.push((post.postId, content_hash, post_value));
+        }
+
+        // ...then write them out, up to `concurrency` at a time.
+        let results: Vec<eyre::Result<(String, String)>> = stream::iter(to_write)
+            .map(|(post_id, content_hash, post_value)| {
+                let path = output_path.join(format!("{post_id}.json"));
+                async move {
+                    info!("Writing {path:?}");
+                    let output_file = File::create(path)?;
+                    serde_json::to_writer(output_file, &post_value)?;
+                    Ok((post_id, content_hash))
+                }
+            })
+            .buffer_unordered(args.concurrency.max(1))
+            .collect()
+            .await;
+
+        for result in results {
+            let (post_id, content_hash) = result?;
+            index.posts.insert(post_id, IndexedPost { content_hash });
+        }
+
+        if let Some(etag) = etag {
+            index.page_etags.insert(page, etag);
+        }
+        index.save(&index_path)?;
+
+        if !any_new_or_changed {
+            debug!("page {page} had nothing new or changed; stopping early");
+            break 'pages;
         }
     }
 
@@ -0,0 +1,49 @@
+use std::{fs, path::Path};
+
+use jane_eyre::eyre;
+use tracing::info;
+
+use crate::cohost_client::build_client;
+use crate::dom::{parse, serialize};
+use crate::media::MediaArchiver;
+
+#[derive(clap::Args, Debug)]
+pub struct Media {
+    pub path_to_chosts: String,
+
+    /// how many media files to download at once.
+    #[arg(long, default_value_t = 8)]
+    pub concurrency: usize,
+
+    /// maximum number of requests to make per second, across retries.
+    #[arg(long, default_value_t = 4)]
+    pub requests_per_second: u32,
+}
+
+pub async fn main(args: Media) -> eyre::Result<()> {
+    let output_path = Path::new(&args.path_to_chosts);
+    let client = build_client()?;
+    let mut archiver = MediaArchiver::new(
+        &client,
+        output_path,
+        args.concurrency,
+        args.requests_per_second,
+    )?;
+
+    for entry in fs::read_dir(output_path)? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.extension().and_then(|extension| extension.to_str()) != Some("html") {
+            continue;
+        }
+
+        info!("archiving media referenced by {path:?}");
+        let html = fs::read_to_string(&path)?;
+        let dom = parse(html.as_bytes())?;
+        let root = dom.document.children.borrow()[0].clone();
+        archiver.archive(root).await?;
+        fs::write(&path, serialize(dom)?)?;
+    }
+
+    Ok(())
+}
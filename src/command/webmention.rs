@@ -0,0 +1,47 @@
+use std::{fs, path::Path};
+
+use jane_eyre::eyre;
+use reqwest::Client;
+use tracing::info;
+
+use crate::webmention::Webmentions;
+
+#[derive(clap::Args, Debug)]
+pub struct Webmention {
+    /// the base url the archive is (or will be) served from, used to derive each post’s public
+    /// url, e.g. `https://example.com/chosts`. a webmention receiver fetches `source` to confirm
+    /// it really links to `target`, so this has to be a url the receiver can reach, not a local
+    /// path.
+    pub site_url: String,
+    pub path_to_chosts: String,
+
+    /// print discovered source/target pairs instead of sending webmentions.
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+pub async fn main(args: Webmention) -> eyre::Result<()> {
+    let output_path = Path::new(&args.path_to_chosts);
+    let site_url = args.site_url.trim_end_matches('/');
+    let client = Client::builder().build()?;
+    let mut webmentions = Webmentions::new(&client, output_path)?;
+
+    for entry in fs::read_dir(output_path)? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.extension().and_then(|extension| extension.to_str()) != Some("html") {
+            continue;
+        }
+
+        let relative_path = path.strip_prefix(output_path)?;
+        let source = format!("{site_url}/{}", relative_path.display());
+
+        info!("scanning {path:?} for outgoing links");
+        let source_html = fs::read_to_string(&path)?;
+        webmentions
+            .process(&source, &source_html, args.dry_run)
+            .await?;
+    }
+
+    Ok(())
+}
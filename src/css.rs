@@ -0,0 +1,362 @@
+//! sanitizes inline `style` attribute values, so that archived posts cannot carry executable or
+//! exfiltrating CSS through to the rendered output.
+//!
+//! each value is parsed as a real CSS declaration list with [`cssparser`] 0.37 (so quoted
+//! strings, nested functions, and comments are handled the way a browser would, not by splitting
+//! on `;`), declarations whose property is not on [`KNOWN_GOOD_PROPERTIES`] are dropped, and any
+//! `url(...)` argument whose scheme is not `https:` or relative (or that is a
+//! `javascript:`/`expression(` payload) is rejected outright.
+
+use std::{
+    collections::BTreeSet,
+    sync::{LazyLock, Mutex},
+};
+
+use cssparser::{
+    AtRuleParser, CowRcStr, DeclarationParser, ParseError, Parser, ParserInput, ParserState,
+    QualifiedRuleParser, RuleBodyItemParser, RuleBodyParser, Token, ToCss,
+};
+use tracing::warn;
+
+static REJECTED_PROPERTIES_SEEN: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+static KNOWN_GOOD_PROPERTIES: LazyLock<BTreeSet<&'static str>> = LazyLock::new(|| {
+    let mut result = BTreeSet::default();
+    result.insert("background");
+    result.insert("background-color");
+    result.insert("background-image");
+    result.insert("border");
+    result.insert("border-color");
+    result.insert("border-radius");
+    result.insert("color");
+    result.insert("float");
+    result.insert("font-size");
+    result.insert("font-style");
+    result.insert("font-weight");
+    result.insert("height");
+    result.insert("margin");
+    result.insert("padding");
+    result.insert("text-align");
+    result.insert("text-decoration");
+    result.insert("width");
+    result
+});
+
+/// sanitizes a `style` attribute value, returning the re-serialized, surviving declarations, or
+/// `None` if none survived (in which case the caller should drop the attribute entirely).
+pub fn sanitize_style(value: &str) -> Option<String> {
+    let mut input = ParserInput::new(value);
+    let mut parser = Parser::new(&mut input);
+
+    let declarations: Vec<(String, String)> =
+        RuleBodyParser::new(&mut parser, &mut StyleDeclarationParser)
+            .filter_map(|result| result.ok())
+            .collect();
+
+    if declarations.is_empty() {
+        return None;
+    }
+
+    Some(
+        declarations
+            .into_iter()
+            .map(|(property, value)| format!("{property}: {value}"))
+            .collect::<Vec<_>>()
+            .join("; "),
+    )
+}
+
+/// parses one `property: value` declaration, dropping it (by returning `Err`) if the property is
+/// not known-good or the value contains a disallowed `url(...)`.
+///
+/// `cssparser`'s `RuleBodyParser` always needs a parser that can in principle also handle at-rules
+/// and qualified rules (a declaration list can contain either), so we give it empty
+/// [`AtRuleParser`]/[`QualifiedRuleParser`] impls and tell [`RuleBodyItemParser`] to only attempt
+/// declarations.
+struct StyleDeclarationParser;
+
+impl<'i> DeclarationParser<'i> for StyleDeclarationParser {
+    type Declaration = (String, String);
+    type Error = ();
+
+    fn parse_value<'t>(
+        &mut self,
+        name: CowRcStr<'i>,
+        input: &mut Parser<'i, 't>,
+        declaration_start: &ParserState,
+    ) -> Result<Self::Declaration, ParseError<'i, Self::Error>> {
+        let property = name.to_lowercase();
+        if !KNOWN_GOOD_PROPERTIES.contains(property.as_str()) {
+            warn!("dropping style declaration for property not on known-good-properties list: check if output is correct for: {property}");
+            reject(&property);
+            return Err(input.new_custom_error(()));
+        }
+
+        let mut serialized = String::new();
+        loop {
+            let token = match input.next_including_whitespace() {
+                Ok(token) => token.clone(),
+                Err(_) => break,
+            };
+
+            match &token {
+                Token::WhiteSpace(_) => {
+                    if !serialized.is_empty() && !serialized.ends_with(' ') {
+                        serialized.push(' ');
+                    }
+                    continue;
+                }
+                // normalize both `url(foo)` and `url("foo")` to the same quoted form, so the
+                // surviving output is consistent no matter which syntax the author used.
+                Token::UnquotedUrl(url) => {
+                    if !url_is_safe(url) {
+                        warn!("dropping style declaration with disallowed url: {property}: {url}");
+                        reject(&property);
+                        return Err(input.new_custom_error(()));
+                    }
+                    serialized.push_str("url(\"");
+                    serialized.push_str(url);
+                    serialized.push_str("\")");
+                    continue;
+                }
+                Token::Function(name) if name.eq_ignore_ascii_case("expression") => {
+                    warn!("dropping style declaration containing expression(): {property}");
+                    reject(&property);
+                    return Err(input.new_custom_error(()));
+                }
+                Token::Function(name) if name.eq_ignore_ascii_case("url") => {
+                    let url =
+                        parse_url_function(input).map_err(|_| input.new_custom_error(()))?;
+                    if !url_is_safe(&url) {
+                        warn!("dropping style declaration with disallowed url: {property}: {url}");
+                        reject(&property);
+                        return Err(input.new_custom_error(()));
+                    }
+                    serialized.push_str("url(\"");
+                    serialized.push_str(&url);
+                    serialized.push_str("\")");
+                    continue;
+                }
+                // an unquoted `url(` whose argument contains `(`, a quote, or whitespace in the
+                // middle, e.g. `url(javascript:alert(1))`.
+                Token::BadUrl(_) => {
+                    warn!("dropping style declaration with malformed url: {property}");
+                    reject(&property);
+                    return Err(input.new_custom_error(()));
+                }
+                _ => {}
+            }
+
+            let _ = token.to_css(&mut serialized);
+
+            // `to_css` only writes the opening of a function or block, so serialize its contents
+            // with the same checks, then close it.
+            let closing = match &token {
+                Token::Function(_) | Token::ParenthesisBlock => Some(')'),
+                Token::SquareBracketBlock => Some(']'),
+                Token::CurlyBracketBlock => Some('}'),
+                _ => None,
+            };
+            if let Some(closing) = closing {
+                let (_, inner) = input
+                    .parse_nested_block(|input| self.parse_value(name.clone(), input, declaration_start))?;
+                serialized.push_str(&inner);
+                serialized.push(closing);
+            }
+        }
+
+        Ok((property, serialized.trim().to_owned()))
+    }
+}
+
+impl<'i> AtRuleParser<'i> for StyleDeclarationParser {
+    type Prelude = ();
+    type AtRule = (String, String);
+    type Error = ();
+}
+
+impl<'i> QualifiedRuleParser<'i> for StyleDeclarationParser {
+    type Prelude = ();
+    type QualifiedRule = (String, String);
+    type Error = ();
+}
+
+impl<'i> RuleBodyItemParser<'i, (String, String), ()> for StyleDeclarationParser {
+    fn parse_declarations(&self) -> bool {
+        true
+    }
+
+    fn parse_qualified(&self) -> bool {
+        false
+    }
+}
+
+/// consumes a `url(...)` function’s nested block (the parser must be positioned right after its
+/// `Token::Function("url")`), returning its quoted-string argument. a bare `url(foo)` token
+/// without quotes is not a function at all ([`Token::UnquotedUrl`]) and is handled separately by
+/// callers.
+fn parse_url_function<'i, 't>(input: &mut Parser<'i, 't>) -> Result<String, ParseError<'i, ()>> {
+    input.parse_nested_block(|input| {
+        input
+            .expect_string()
+            .map(|value| value.as_ref().to_owned())
+            .map_err(|error| error.into())
+    })
+}
+
+/// every top-level `url(...)` argument referenced by a css value, in the order it appears — e.g.
+/// every asset a `background`/`background-image` declaration points at. a `url(...)` nested
+/// inside another function (such as `image-set(...)`) is not found. unlike [`sanitize_style`], this
+/// does not care whether the property or the url itself is safe; it is for callers (like
+/// [`crate::media::MediaArchiver`]) that just need to find assets to mirror, not to sanitize
+/// anything for rendering.
+pub fn style_urls(value: &str) -> Vec<String> {
+    let mut input = ParserInput::new(value);
+    let mut parser = Parser::new(&mut input);
+    let mut urls = vec![];
+
+    loop {
+        let token = match parser.next_including_whitespace() {
+            Ok(token) => token.clone(),
+            Err(_) => break,
+        };
+
+        match &token {
+            Token::UnquotedUrl(url) => urls.push(url.as_ref().to_owned()),
+            Token::Function(name) if name.eq_ignore_ascii_case("url") => {
+                if let Ok(url) = parse_url_function(&mut parser) {
+                    urls.push(url);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    urls
+}
+
+fn reject(property: &str) {
+    REJECTED_PROPERTIES_SEEN
+        .lock()
+        .unwrap()
+        .insert(property.to_owned());
+}
+
+/// a `url(...)` argument is safe if it is `https:`, or has no scheme at all (a same-origin
+/// relative path). anything else — `javascript:`, `data:`, and crucially a protocol-relative
+/// `//host/path` url, which would fetch an arbitrary host under the page’s own scheme — is
+/// rejected.
+fn url_is_safe(url: &str) -> bool {
+    let url = url.trim();
+    if url.starts_with("//") {
+        return false;
+    }
+    if url.to_lowercase().starts_with("javascript:") {
+        return false;
+    }
+
+    !url.contains(':') || url.to_lowercase().starts_with("https:")
+}
+
+/// properties we have seen and dropped from a `style` attribute, so authors can audit what was
+/// stripped, the way [`crate::dom::debug_not_known_good_attributes_seen`] does for attributes.
+pub fn debug_rejected_properties_seen() -> Vec<String> {
+    REJECTED_PROPERTIES_SEEN
+        .lock()
+        .unwrap()
+        .iter()
+        .cloned()
+        .collect()
+}
+
+#[test]
+fn test_sanitize_style_keeps_known_good_declarations() {
+    assert_eq!(
+        sanitize_style("color: red; font-weight: bold"),
+        Some("color: red; font-weight: bold".to_owned()),
+    );
+}
+
+#[test]
+fn test_sanitize_style_drops_unknown_property() {
+    assert_eq!(sanitize_style("position: fixed; top: 0"), None);
+}
+
+#[test]
+fn test_sanitize_style_drops_some_but_not_all() {
+    assert_eq!(
+        sanitize_style("color: red; position: fixed"),
+        Some("color: red".to_owned()),
+    );
+}
+
+#[test]
+fn test_sanitize_style_drops_javascript_url() {
+    assert_eq!(
+        sanitize_style("background: url(javascript:alert(1))"),
+        None,
+    );
+}
+
+#[test]
+fn test_sanitize_style_drops_non_https_url() {
+    assert_eq!(
+        sanitize_style("background-image: url(ftp://evil.example/x.png)"),
+        None,
+    );
+}
+
+#[test]
+fn test_sanitize_style_drops_protocol_relative_url() {
+    assert_eq!(
+        sanitize_style("background-image: url(//evil.example/x.png)"),
+        None,
+    );
+    assert_eq!(
+        sanitize_style(r#"background-image: url("//evil.example/x.png")"#),
+        None,
+    );
+}
+
+#[test]
+fn test_sanitize_style_keeps_https_and_relative_urls() {
+    assert_eq!(
+        sanitize_style("background-image: url(https://cohostcdn.org/a.png)"),
+        Some(r#"background-image: url("https://cohostcdn.org/a.png")"#.to_owned()),
+    );
+    assert_eq!(
+        sanitize_style("background-image: url(/a.png)"),
+        Some(r#"background-image: url("/a.png")"#.to_owned()),
+    );
+}
+
+#[test]
+fn test_sanitize_style_handles_semicolons_inside_quoted_values() {
+    // a naive split on `;` would cut this declaration in half.
+    assert_eq!(
+        sanitize_style(r#"color: red; background: url("https://cohostcdn.org/a;b.png")"#),
+        Some(r#"color: red; background: url("https://cohostcdn.org/a;b.png")"#.to_owned()),
+    );
+}
+
+#[test]
+fn test_style_urls_finds_both_quoted_and_unquoted_forms() {
+    assert_eq!(
+        style_urls("background: url(https://cohostcdn.org/a.png) no-repeat"),
+        vec!["https://cohostcdn.org/a.png".to_owned()],
+    );
+    assert_eq!(
+        style_urls("background-image: url('https://cohostcdn.org/a.png')"),
+        vec!["https://cohostcdn.org/a.png".to_owned()],
+    );
+    assert_eq!(style_urls("color: red"), Vec::<String>::new());
+}
+
+#[test]
+fn test_style_urls_does_not_get_confused_by_semicolons_inside_quoted_values() {
+    // a naive split on `;` would cut this url in half.
+    assert_eq!(
+        style_urls(r#"background: url("https://cohostcdn.org/a;b.png")"#),
+        vec!["https://cohostcdn.org/a;b.png".to_owned()],
+    );
+}
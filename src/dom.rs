@@ -253,6 +253,7 @@ pub fn convert_idl_to_content_attribute(
     Some(Attribute {
         name: rename_idl_to_content_attribute(tag_name, attribute_name),
         value: match (tag_name, attribute_name, value) {
+            (_, "style", Value::String(value)) => crate::css::sanitize_style(&value)?.into(),
             (_, _, Value::String(value)) => value.into(),
             (_, _, Value::Number(value)) => value.to_string().into(),
             (_, _, Value::Bool(true)) => "".into(),
@@ -313,6 +314,25 @@ fn test_convert_idl_to_content_attribute() {
             value: "foo bar".into(),
         }),
     );
+    assert_eq!(
+        convert_idl_to_content_attribute(
+            "div",
+            "style",
+            Value::String("color: red; position: fixed".to_owned()),
+        ),
+        Some(Attribute {
+            name: make_attribute_name("style"),
+            value: "color: red".into(),
+        }),
+    );
+    assert_eq!(
+        convert_idl_to_content_attribute(
+            "div",
+            "style",
+            Value::String("position: fixed".to_owned()),
+        ),
+        None,
+    );
 }
 
 pub fn debug_attributes_seen() -> Vec<(String, String)> {
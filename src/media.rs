@@ -0,0 +1,369 @@
+//! Downloads assets referenced by a converted post (`img src`, `a href`, and CSS `url(...)` in
+//! `style`) into a local `media/` directory, and rewrites the DOM to point at the local copies.
+//!
+//! This is what turns an archive into a self-contained copy of a chost, instead of a pile of
+//! markup that still depends on cohost’s CDN staying up.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::{self, File},
+    io::Write as _,
+    path::{Path, PathBuf},
+};
+
+use futures::stream::{self, StreamExt};
+use jane_eyre::eyre::{self, eyre};
+use markup5ever_rcdom::{Handle, NodeData};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+use crate::css::style_urls;
+use crate::dom::{find_attr_mut, tendril_to_str, Traverse};
+#[cfg(test)]
+use crate::dom::{attr_value, create_element, make_attribute_name};
+use crate::retry::{get_with_retry, RateLimiter};
+
+/// (tag name, attribute name) pairs that may carry a url worth archiving.
+const ARCHIVABLE_ATTRIBUTES: &[(&str, &str)] = &[("img", "src"), ("a", "href")];
+
+/// hosts whose urls point at cohost-hosted attachments, and are therefore worth mirroring.
+/// anything else (e.g. a link to another website in a chost’s body) is left alone.
+const ARCHIVABLE_HOSTS: &[&str] = &["cohostcdn.org", "staging.cohostcdn.org"];
+
+/// the name of the index file we leave in the archive’s output directory, mapping source url to
+/// local path so a repeated run can skip the network fetch entirely, not just the write.
+const INDEX_FILENAME: &str = ".media-index.json";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Index {
+    by_url: BTreeMap<String, String>,
+}
+
+impl Index {
+    fn load(path: &Path) -> eyre::Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(serde_json::from_reader(File::open(path)?)?)
+    }
+
+    fn save(&self, path: &Path) -> eyre::Result<()> {
+        serde_json::to_writer(File::create(path)?, self)?;
+        Ok(())
+    }
+}
+
+/// downloads and deduplicates media for one archive, keyed by the sha256 of its content so that
+/// the same attachment referenced by multiple chosts is only ever stored once, and by source url
+/// (persisted across runs) so a url already archived is never re-fetched at all.
+pub struct MediaArchiver<'client> {
+    client: &'client Client,
+    media_path: PathBuf,
+    index_path: PathBuf,
+    index: Index,
+    by_content_hash: BTreeMap<String, PathBuf>,
+    rate_limiter: RateLimiter,
+    concurrency: usize,
+}
+
+impl<'client> MediaArchiver<'client> {
+    pub fn new(
+        client: &'client Client,
+        output_path: &Path,
+        concurrency: usize,
+        requests_per_second: u32,
+    ) -> eyre::Result<Self> {
+        let media_path = output_path.join("media");
+        fs::create_dir_all(&media_path)?;
+
+        let index_path = output_path.join(INDEX_FILENAME);
+        let index = Index::load(&index_path)?;
+
+        // repopulate the content-hash index from whatever is already on disk too, so an asset
+        // the index does not know the url for yet (e.g. from a run before the index existed)
+        // still dedupes by content once downloaded.
+        let mut by_content_hash = BTreeMap::default();
+        for entry in fs::read_dir(&media_path)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            if let Some(content_hash) = entry.file_name().to_str().and_then(content_hash_of_name) {
+                by_content_hash.insert(content_hash.to_owned(), entry.path());
+            }
+        }
+
+        Ok(Self {
+            client,
+            media_path,
+            index_path,
+            index,
+            by_content_hash,
+            rate_limiter: RateLimiter::new(requests_per_second),
+            concurrency,
+        })
+    }
+
+    /// walk `root`, downloading every archivable url it references and rewriting the
+    /// corresponding attribute to the relative local path. a url that fails to download (e.g. a
+    /// 404 for a since-deleted attachment) is logged and left unrewritten, rather than aborting
+    /// the rest of this post’s (or the run’s remaining posts’) media.
+    pub async fn archive(&mut self, root: Handle) -> eyre::Result<()> {
+        // collect (node, attribute name, url) first, so we never hold a RefCell borrow of a
+        // node’s attributes across an `.await`.
+        let mut targets = vec![];
+        for node in Traverse::new(root) {
+            let NodeData::Element { name, attrs, .. } = &node.data else {
+                continue;
+            };
+            let tag_name = name.local.as_ref();
+            for (element_tag, attribute_name) in ARCHIVABLE_ATTRIBUTES {
+                if tag_name != *element_tag {
+                    continue;
+                }
+                let attrs = attrs.borrow();
+                if let Some(attr) = attrs
+                    .iter()
+                    .find(|attr| attr.name.local.as_ref() == *attribute_name)
+                {
+                    let url = tendril_to_str(&attr.value)?.to_owned();
+                    if is_archivable_url(&url) {
+                        targets.push((node.clone(), *attribute_name, url));
+                    }
+                }
+            }
+
+            if tag_name != "html" {
+                if let Some(style) = attrs
+                    .borrow()
+                    .iter()
+                    .find(|attr| attr.name.local.as_ref() == "style")
+                {
+                    let style = tendril_to_str(&style.value)?.to_owned();
+                    for url in style_urls(&style) {
+                        if is_archivable_url(&url) {
+                            targets.push((node.clone(), "style", url.clone()));
+                        }
+                    }
+                }
+            }
+        }
+
+        // figure out, synchronously, which urls are not already archived, deduping so the same
+        // asset referenced by several attributes is only ever fetched once...
+        let mut to_fetch = vec![];
+        let mut seen = BTreeSet::new();
+        for (_, _, url) in &targets {
+            if self.index.by_url.contains_key(url) || !seen.insert(url.clone()) {
+                continue;
+            }
+            to_fetch.push(url.clone());
+        }
+
+        // ...then fetch them, up to `concurrency` at a time.
+        let client = self.client;
+        let rate_limiter = &self.rate_limiter;
+        let results: Vec<(String, eyre::Result<(Vec<u8>, &'static str)>)> = stream::iter(to_fetch)
+            .map(|url| async move {
+                let result = fetch_media(client, rate_limiter, &url).await;
+                (url, result)
+            })
+            .buffer_unordered(self.concurrency.max(1))
+            .collect()
+            .await;
+
+        for (url, result) in results {
+            let (bytes, extension) = match result {
+                Ok(downloaded) => downloaded,
+                Err(error) => {
+                    warn!("failed to archive {url}: {error}");
+                    continue;
+                }
+            };
+            if let Err(error) = self.store(url.clone(), &bytes, extension) {
+                warn!("failed to store archived media for {url}: {error}");
+            }
+        }
+
+        for (node, attribute_name, url) in targets {
+            let Some(local_path) = self.index.by_url.get(&url) else {
+                continue;
+            };
+            rewrite_attribute(&node, attribute_name, &url, local_path)?;
+        }
+
+        Ok(())
+    }
+
+    /// hashes and stores already-downloaded bytes for `url`, deduplicating against any asset
+    /// with the same content we have already archived, and persists the url index.
+    fn store(&mut self, url: String, bytes: &[u8], extension: &'static str) -> eyre::Result<()> {
+        let content_hash = format!("{:x}", Sha256::digest(bytes));
+        let local_path = if let Some(existing) = self.by_content_hash.get(&content_hash) {
+            relative_media_path(existing)?
+        } else {
+            let filename = format!("{content_hash}{extension}");
+            let path = self.media_path.join(&filename);
+            let mut file = File::create(&path)?;
+            file.write_all(bytes)?;
+
+            self.by_content_hash.insert(content_hash, path.clone());
+            relative_media_path(&path)?
+        };
+
+        self.index.by_url.insert(url, local_path);
+        self.index.save(&self.index_path)?;
+
+        Ok(())
+    }
+}
+
+/// downloads `url`, retrying on connection errors and 429/5xx responses with exponential
+/// backoff, returning its bytes and the file extension its content-type implies.
+async fn fetch_media(
+    client: &Client,
+    rate_limiter: &RateLimiter,
+    url: &str,
+) -> eyre::Result<(Vec<u8>, &'static str)> {
+    info!("GET {url}");
+    let response = get_with_retry(client, rate_limiter, url, None)
+        .await?
+        .error_for_status()?;
+    let extension = extension_for_content_type(
+        response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok()),
+    );
+    let bytes = response.bytes().await?.to_vec();
+
+    Ok((bytes, extension))
+}
+
+/// points `attribute_name` on `node` at `local_path` instead of `url`. for `style`, `url` only
+/// occurs inside a `url(...)` argument, so we substitute it in place rather than replacing the
+/// whole attribute value.
+fn rewrite_attribute(
+    node: &Handle,
+    attribute_name: &'static str,
+    url: &str,
+    local_path: &str,
+) -> eyre::Result<()> {
+    let NodeData::Element { attrs, .. } = &node.data else {
+        return Ok(());
+    };
+    let mut attrs = attrs.borrow_mut();
+    if attribute_name == "style" {
+        if let Some(attr) = find_attr_mut(&mut attrs, "style") {
+            let rewritten = tendril_to_str(&attr.value)?.replace(url, local_path);
+            attr.value = rewritten.into();
+        }
+    } else if let Some(attr) = find_attr_mut(&mut attrs, attribute_name) {
+        attr.value = local_path.into();
+    }
+
+    Ok(())
+}
+
+fn relative_media_path(path: &Path) -> eyre::Result<String> {
+    Ok(format!(
+        "media/{}",
+        path.file_name()
+            .ok_or_else(|| eyre!("media path has no file name: {path:?}"))?
+            .to_str()
+            .ok_or_else(|| eyre!("media filename is not valid utf-8: {path:?}"))?
+    ))
+}
+
+fn content_hash_of_name(filename: &str) -> Option<&str> {
+    filename.split('.').next().filter(|hash| !hash.is_empty())
+}
+
+fn is_archivable_url(url: &str) -> bool {
+    let Ok(url) = reqwest::Url::parse(url) else {
+        return false;
+    };
+
+    url.host_str()
+        .is_some_and(|host| ARCHIVABLE_HOSTS.contains(&host))
+}
+
+fn extension_for_content_type(content_type: Option<&str>) -> &'static str {
+    match content_type.and_then(|value| value.split(';').next()) {
+        Some("image/png") => ".png",
+        Some("image/jpeg") => ".jpg",
+        Some("image/gif") => ".gif",
+        Some("image/webp") => ".webp",
+        Some("image/avif") => ".avif",
+        Some("image/svg+xml") => ".svg",
+        Some("video/mp4") => ".mp4",
+        Some(other) => {
+            warn!("unknown content-type for archived media, storing without extension: {other}");
+            ""
+        }
+        None => "",
+    }
+}
+
+#[test]
+fn test_is_archivable_url() {
+    assert!(is_archivable_url(
+        "https://staging.cohostcdn.org/attachment/abc/image.png"
+    ));
+    assert!(!is_archivable_url("https://example.com/image.png"));
+    assert!(!is_archivable_url("not a url"));
+}
+
+/// proves the rewriting step `archive` relies on points attributes at the relative local path,
+/// not just that it decides which urls are archivable (see `test_is_archivable_url` above). we test
+/// `rewrite_attribute` directly, rather than the whole network-fetching `archive`, so this does
+/// not depend on a network fetch.
+#[test]
+fn test_rewrite_attribute_rewrites_img_src_and_style_url() -> eyre::Result<()> {
+    let mut dom = crate::dom::create_fragment().0;
+
+    let img = create_element(&mut dom, "img");
+    push_attr(&img, "src", "https://cohostcdn.org/a.png");
+    rewrite_attribute(&img, "src", "https://cohostcdn.org/a.png", "media/abc.png")?;
+    let NodeData::Element { attrs, .. } = &img.data else {
+        unreachable!()
+    };
+    assert_eq!(attr_value(&attrs.borrow(), "src")?, Some("media/abc.png"));
+
+    let div = create_element(&mut dom, "div");
+    push_attr(
+        &div,
+        "style",
+        "background-image: url(https://cohostcdn.org/b.png)",
+    );
+    rewrite_attribute(
+        &div,
+        "style",
+        "https://cohostcdn.org/b.png",
+        "media/def.png",
+    )?;
+    let NodeData::Element { attrs, .. } = &div.data else {
+        unreachable!()
+    };
+    assert_eq!(
+        attr_value(&attrs.borrow(), "style")?,
+        Some("background-image: url(media/def.png)"),
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+fn push_attr(node: &Handle, name: &str, value: &str) {
+    use html5ever::Attribute;
+
+    let NodeData::Element { attrs, .. } = &node.data else {
+        unreachable!()
+    };
+    attrs.borrow_mut().push(Attribute {
+        name: make_attribute_name(name),
+        value: value.into(),
+    });
+}
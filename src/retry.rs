@@ -0,0 +1,117 @@
+//! Shared request retrying and rate limiting for anything that walks a list of urls over http:
+//! `cohost2json`'s page fetches and `media`'s asset downloads both want the same "don't hammer
+//! the far end, and don't give up on the first 429" behaviour.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use jane_eyre::eyre;
+use reqwest::{
+    header::{self, HeaderMap},
+    Client, Response, StatusCode,
+};
+use tokio::time::sleep;
+use tracing::warn;
+
+/// the maximum number of attempts to make for one request before giving up, including the
+/// first.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// politely spaces out requests so a multi-thousand-item run does not hammer the far end.
+pub struct RateLimiter {
+    min_interval: Duration,
+    next_request_at: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(requests_per_second: u32) -> Self {
+        Self {
+            min_interval: Duration::from_secs(1) / requests_per_second.max(1),
+            next_request_at: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub async fn acquire(&self) {
+        let wait_until = {
+            let mut next_request_at = self.next_request_at.lock().unwrap();
+            let wait_until = (*next_request_at).max(Instant::now());
+            *next_request_at = wait_until + self.min_interval;
+            wait_until
+        };
+        sleep(wait_until.saturating_duration_since(Instant::now())).await;
+    }
+}
+
+/// `GET`s `url`, retrying on connection errors and 429/5xx responses with exponential backoff,
+/// honouring any `Retry-After` header the server sends us.
+pub async fn get_with_retry(
+    client: &Client,
+    rate_limiter: &RateLimiter,
+    url: &str,
+    if_none_match: Option<&str>,
+) -> eyre::Result<Response> {
+    for attempt in 1.. {
+        rate_limiter.acquire().await;
+
+        let mut request = client.get(url);
+        if let Some(etag) = if_none_match {
+            request = request.header(header::IF_NONE_MATCH, etag);
+        }
+
+        let outcome = request.send().await;
+        let should_retry = match &outcome {
+            Ok(response) => {
+                response.status() == StatusCode::TOO_MANY_REQUESTS
+                    || response.status().is_server_error()
+            }
+            Err(_) => true,
+        };
+
+        if !should_retry || attempt >= MAX_ATTEMPTS {
+            return Ok(outcome?);
+        }
+
+        let delay = outcome
+            .as_ref()
+            .ok()
+            .and_then(|response| retry_after(response.headers()))
+            .unwrap_or_else(|| backoff_delay(attempt));
+        warn!("GET {url} failed (attempt {attempt}/{MAX_ATTEMPTS}); retrying in {delay:?}");
+        sleep(delay).await;
+    }
+
+    unreachable!("the loop above always returns by MAX_ATTEMPTS")
+}
+
+fn backoff_delay(attempt: u32) -> Duration {
+    Duration::from_millis(500 * 2u64.pow(attempt.min(6)))
+}
+
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(header::RETRY_AFTER)?.to_str().ok()?;
+    value.parse::<u64>().ok().map(Duration::from_secs)
+}
+
+#[test]
+fn test_backoff_delay() {
+    assert_eq!(backoff_delay(1), Duration::from_millis(1000));
+    assert_eq!(backoff_delay(2), Duration::from_millis(2000));
+    assert_eq!(backoff_delay(3), Duration::from_millis(4000));
+    // attempt is capped at 6 so a long-running retry loop does not sleep for longer and longer.
+    assert_eq!(backoff_delay(6), backoff_delay(10));
+}
+
+#[test]
+fn test_retry_after() {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::RETRY_AFTER, "30".parse().unwrap());
+    assert_eq!(retry_after(&headers), Some(Duration::from_secs(30)));
+
+    assert_eq!(retry_after(&HeaderMap::new()), None);
+
+    let mut not_a_number = HeaderMap::new();
+    not_a_number.insert(header::RETRY_AFTER, "Wed, 21 Oct 2026 07:28:00 GMT".parse().unwrap());
+    assert_eq!(retry_after(&not_a_number), None);
+}
@@ -0,0 +1,275 @@
+//! IndieWeb webmention discovery and sending for a rendered archive’s outgoing links.
+//!
+//! given a source page we have already rendered, we walk its `a href`s with [`Traverse`], look
+//! up a webmention endpoint for each external target the way a live IndieWeb site would (the
+//! target’s `Link: rel="webmention"` response header, falling back to a `<link>`/`<a
+//! rel="webmention">` in its html), and POST a `source`/`target` form body to it.
+
+use std::{
+    collections::BTreeSet,
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use jane_eyre::eyre;
+use markup5ever_rcdom::NodeData;
+use reqwest::{header, Client, Url};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::dom::{attr_value, parse, Traverse};
+
+/// the name of the log file we leave in the archive’s output directory, tracking which
+/// (source, target) pairs we have already sent a webmention for.
+const LOG_FILENAME: &str = ".webmention-log.json";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Log {
+    sent: BTreeSet<(String, String)>,
+}
+
+impl Log {
+    fn load(path: &Path) -> eyre::Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(serde_json::from_reader(File::open(path)?)?)
+    }
+
+    fn save(&self, path: &Path) -> eyre::Result<()> {
+        serde_json::to_writer(File::create(path)?, self)?;
+        Ok(())
+    }
+}
+
+/// what `send_one` actually did, so callers can tell "nothing to send yet" apart from "sent",
+/// instead of conflating both into a bare `Ok(())` that would get logged as sent either way.
+#[derive(Debug, PartialEq, Eq)]
+enum SendOutcome {
+    /// no webmention endpoint could be discovered for the target this run; it stays eligible for
+    /// retry on a later run rather than being logged as sent.
+    NoEndpoint,
+    Sent,
+}
+
+pub struct Webmentions<'client> {
+    client: &'client Client,
+    log_path: PathBuf,
+    log: Log,
+}
+
+impl<'client> Webmentions<'client> {
+    pub fn new(client: &'client Client, output_path: &Path) -> eyre::Result<Self> {
+        let log_path = output_path.join(LOG_FILENAME);
+        let log = Log::load(&log_path)?;
+
+        Ok(Self {
+            client,
+            log_path,
+            log,
+        })
+    }
+
+    /// every external `a href` target linked from `source_html`.
+    pub fn targets_in(&self, source_html: &str) -> eyre::Result<Vec<String>> {
+        let dom = parse(source_html.as_bytes())?;
+        let mut result = vec![];
+        for node in Traverse::new(dom.document.children.borrow()[0].clone()) {
+            let NodeData::Element { name, attrs, .. } = &node.data else {
+                continue;
+            };
+            if name.local.as_ref() != "a" {
+                continue;
+            }
+            let attrs = attrs.borrow();
+            if let Some(href) = attr_value(&attrs, "href")? {
+                if href.starts_with("http://") || href.starts_with("https://") {
+                    result.push(href.to_owned());
+                }
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// send (or, in `dry_run`, just print) a webmention from `source` to every not-yet-notified
+    /// target discovered in `source_html`. a target that rejects the webmention (or cannot be
+    /// reached) gets a warning and is skipped rather than aborting the rest of the post’s
+    /// targets; like a target whose endpoint cannot be discovered this run, it is not recorded
+    /// in the send log, so it stays eligible for a later run to retry.
+    pub async fn process(
+        &mut self,
+        source: &str,
+        source_html: &str,
+        dry_run: bool,
+    ) -> eyre::Result<()> {
+        for target in self.targets_in(source_html)? {
+            if self.log.sent.contains(&(source.to_owned(), target.clone())) {
+                continue;
+            }
+
+            if dry_run {
+                println!("{source} -> {target}");
+                continue;
+            }
+
+            let outcome = match self.send_one(source, &target).await {
+                Ok(outcome) => outcome,
+                Err(error) => {
+                    warn!("failed to send webmention {source} -> {target}: {error}");
+                    continue;
+                }
+            };
+
+            // a target with no discoverable endpoint stays unlogged, so it is retried on a
+            // later run in case it grows one (e.g. the target starts supporting webmentions).
+            if outcome == SendOutcome::NoEndpoint {
+                continue;
+            }
+
+            self.log.sent.insert((source.to_owned(), target));
+            self.log.save(&self.log_path)?;
+        }
+
+        Ok(())
+    }
+
+    async fn send_one(&self, source: &str, target: &str) -> eyre::Result<SendOutcome> {
+        let Some(endpoint) = self.discover_endpoint(target).await? else {
+            warn!("no webmention endpoint found for {target}; skipping");
+            return Ok(SendOutcome::NoEndpoint);
+        };
+
+        info!("POST {endpoint} (source={source}, target={target})");
+        self.client
+            .post(endpoint)
+            .form(&[("source", source), ("target", target)])
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(SendOutcome::Sent)
+    }
+
+    async fn discover_endpoint(&self, target: &str) -> eyre::Result<Option<String>> {
+        let response = self.client.get(target).send().await?;
+
+        if let Some(link_header) = response.headers().get(header::LINK) {
+            if let Some(endpoint) = parse_link_header(link_header.to_str()?, target) {
+                return Ok(Some(endpoint));
+            }
+        }
+
+        let body = response.text().await?;
+        let dom = parse(body.as_bytes())?;
+        for node in Traverse::new(dom.document.children.borrow()[0].clone()) {
+            let NodeData::Element { name, attrs, .. } = &node.data else {
+                continue;
+            };
+            if !matches!(name.local.as_ref(), "link" | "a") {
+                continue;
+            }
+            let attrs = attrs.borrow();
+            let is_webmention_rel = attr_value(&attrs, "rel")?
+                .is_some_and(|rel| rel.split_whitespace().any(|rel| rel == "webmention"));
+            if is_webmention_rel {
+                if let Some(href) = attr_value(&attrs, "href")? {
+                    return Ok(Some(resolve_url(target, href)?));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// finds a `rel="webmention"` link in an http `Link` header, per RFC 8288.
+fn parse_link_header(value: &str, base: &str) -> Option<String> {
+    for link in value.split(',') {
+        let mut parts = link.split(';');
+        let url = parts.next()?.trim().trim_matches(['<', '>']);
+        let is_webmention = parts.any(|param| {
+            let param = param.trim();
+            param == "rel=webmention" || param == r#"rel="webmention""#
+        });
+        if is_webmention {
+            return resolve_url(base, url).ok();
+        }
+    }
+
+    None
+}
+
+fn resolve_url(base: &str, url: &str) -> eyre::Result<String> {
+    Ok(Url::parse(base)?.join(url)?.to_string())
+}
+
+#[test]
+fn test_targets_in_only_external_http_links() {
+    let client = Client::new();
+    let webmentions = Webmentions {
+        client: &client,
+        log_path: PathBuf::new(),
+        log: Log::default(),
+    };
+
+    let source_html = r#"
+        <a href="https://example.com/a">external https</a>
+        <a href="http://example.com/b">external http</a>
+        <a href="/relative">relative, not a webmention target</a>
+        <a href="mailto:person@example.com">not an http(s) link at all</a>
+    "#;
+
+    assert_eq!(
+        webmentions.targets_in(source_html).unwrap(),
+        vec![
+            "https://example.com/a".to_owned(),
+            "http://example.com/b".to_owned(),
+        ],
+    );
+}
+
+#[test]
+fn test_process_skips_target_already_in_log() {
+    let client = Client::new();
+    let mut webmentions = Webmentions {
+        client: &client,
+        log_path: PathBuf::new(),
+        log: Log::default(),
+    };
+    webmentions.log.sent.insert((
+        "https://example.com/post".to_owned(),
+        "https://example.com/already-sent".to_owned(),
+    ));
+
+    let source_html = r#"<a href="https://example.com/already-sent">already notified</a>"#;
+
+    // if `process` did not skip this pair before trying to discover its webmention endpoint, it
+    // would attempt a real network request here, which (with no tokio runtime in this plain
+    // `#[test]`) panics rather than erroring. completing without panicking proves the
+    // already-logged target was skipped, not attempted.
+    futures::executor::block_on(webmentions.process(
+        "https://example.com/post",
+        source_html,
+        /* dry_run: */ false,
+    ))
+    .unwrap();
+}
+
+#[test]
+fn test_parse_link_header() {
+    assert_eq!(
+        parse_link_header(
+            r#"<https://example.com/webmention>; rel="webmention""#,
+            "https://example.com/post",
+        ),
+        Some("https://example.com/webmention".to_owned()),
+    );
+    assert_eq!(
+        parse_link_header(
+            r#"<https://example.com/feed>; rel="alternate""#,
+            "https://example.com/post",
+        ),
+        None,
+    );
+}